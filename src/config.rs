@@ -5,5 +5,39 @@ use serde::Deserialize;
 pub struct ConfigToml {
     pub wss_port: u16,
     pub osc_port: u16,
-    pub debug: bool
+    pub debug: bool,
+    /// `host:port` targets that inbound WebSocket messages get re-emitted to as OSC.
+    /// Leave empty to keep the bridge one-way (OSC in -> WSS out only).
+    #[serde(default)]
+    pub osc_out_targets: Vec<String>,
+    /// Upper bound, in seconds, on how far into the future an OSC bundle's time tag may
+    /// schedule delivery. Bundles asking for more than this are dropped with a warning
+    /// instead of pinning a sleeping task forever.
+    #[serde(default = "default_max_bundle_delay_secs")]
+    pub max_bundle_delay_secs: u64,
+    /// How OSC messages are re-encoded for delivery over the WebSocket. `Json` (the
+    /// default) decodes and re-serializes every message; `OscBinary` forwards the native
+    /// OSC wire format unchanged, which is cheaper for high-rate control streams.
+    #[serde(default)]
+    pub message_format: MessageFormat,
+    /// Path to a PEM-encoded certificate to serve instead of the self-signed one. Only
+    /// used when `key_path` is also set and both files exist; otherwise falls back to
+    /// generating a self-signed cert.
+    #[serde(default)]
+    pub cert_path: Option<String>,
+    /// Path to the PEM-encoded private key matching `cert_path`.
+    #[serde(default)]
+    pub key_path: Option<String>
+}
+
+fn default_max_bundle_delay_secs() -> u64 {
+    60
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum MessageFormat {
+    #[default]
+    Json,
+    OscBinary
 }
\ No newline at end of file
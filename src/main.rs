@@ -1,80 +1,36 @@
-use async_osc::{OscPacket, OscSocket};
+use async_osc::{OscBundle, OscPacket, OscSocket};
 use futures::{SinkExt, StreamExt};
-use local_ip_address::local_ip;
+use local_ip_address::{list_afinet_netifas, local_ip};
 use openssl::rsa::Rsa;
-use rcgen::{date_time_ymd, CertificateParams, DistinguishedName};
+use rcgen::{date_time_ymd, CertificateParams, DistinguishedName, SanType};
 use serde_json;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, Mutex};
 use warp::Filter;
 
-use std::{fs, net::SocketAddr};
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
 
 mod config;
+mod matcher;
 mod types;
 
 use config::ConfigToml;
 
+/// Control frame a WebSocket client sends to replace its address-pattern subscription, e.g.
+/// `{"subscribe": ["/synth/*", "/mixer/[0-9]/gain"]}`.
+#[derive(serde::Deserialize)]
+struct SubscribeFrame {
+    subscribe: Vec<String>
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Generate new X.509 pub/priv key cert every time the server starts
-    // This is necessary since the computer may have a different local ip address every time,
-    // and the hosted WSS server needs to work over LAN, so 'localhost' wouldn't suffice.
-
-    let local_ip = local_ip().expect("failed to get local ip address");
-    let cert_domain_names = vec!["localhost".to_string(), local_ip.to_string()];
-
-    let mut cert_params: CertificateParams = Default::default();
-    cert_params.not_before = date_time_ymd(2023, 07, 01);
-    cert_params.not_after = date_time_ymd(4096, 01, 01);
-    cert_params.distinguished_name = DistinguishedName::new();
-    cert_params.alg = &rcgen::PKCS_RSA_SHA256;
-
-    fs::create_dir_all("certs")?; // make certs dir if it doesn't exist
-
-    let priv_key_pem = {
-        // If local priv key not already created, make one.
-        if let Ok(pk) = fs::read("./certs/priv_key_rsa.pem") {
-            pk
-        } else {
-            let rsa = Rsa::generate(2048)?;
-            let rsa_pem_str = rsa.private_key_to_pem()?;
-            fs::write("./certs/priv_key_rsa.pem", &rsa_pem_str)?;
-            rsa_pem_str
-        }
-    };
-    let priv_key =
-        Rsa::private_key_from_pem(&priv_key_pem).expect("couldn't parse priv_key_rsa.pem");
-    let pkey =
-        openssl::pkey::PKey::from_rsa(priv_key).expect("couldn't convert priv_key_rsa.pem to pkey");
-    let key_pair_pem = String::from_utf8(
-        pkey.private_key_to_pem_pkcs8()
-            .expect("fail convert to pem pkcs8"),
-    )
-    .unwrap();
-
-    let key_pair = rcgen::KeyPair::from_pem(&key_pair_pem).expect("failed to make KeyPair");
-
-    cert_params.key_pair = Some(key_pair);
-
-    let cert = rcgen::Certificate::from_params(cert_params).expect("failed to make Certificate");
-    let cert_pem_serialized = cert
-        .serialize_pem()
-        .expect("failed to serialize cert to pem");
-    fs::write("./certs/cert.pem", &cert_pem_serialized.as_bytes())
-        .expect("failed to write to file certs/cert.pem");
-    fs::write(
-        "./certs/key.pem",
-        &cert.serialize_private_key_pem().as_bytes(),
-    )
-    .expect("failed to write to file certs/key.pem");
-
-    println!(
-        "Created TLS cert for domains: {}",
-        cert_domain_names.join(", ")
-    );
-
-    // End of cert generation.
-
     // _____________________________________________________________________________________________________________________
     //
     // Read config.toml
@@ -84,10 +40,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let configs: ConfigToml =
         toml::from_str(&config_toml_str).expect("Failed to parse config.toml");
 
+    // _____________________________________________________________________________________________________________________
+    //
+    // Set up the TLS cert: serve the operator's own cert/key if both are configured and
+    // present on disk, otherwise self-sign one.
+    // _____________________________________________________________________________________________________________________
+
+    let (tls_cert_path, tls_key_path) = match (&configs.cert_path, &configs.key_path) {
+        (Some(cert_path), Some(key_path))
+            if Path::new(cert_path).exists() && Path::new(key_path).exists() =>
+        {
+            println!("Using user-supplied TLS cert: {}", cert_path);
+            (cert_path.clone(), key_path.clone())
+        }
+        _ => {
+            generate_self_signed_cert()?;
+            ("./certs/cert.pem".to_string(), "./certs/key.pem".to_string())
+        }
+    };
+
     // Setup OSC receiver.
 
+    let local_ip = local_ip().expect("failed to get local ip address");
     let osc_addr = format!("{}:{}", local_ip, configs.osc_port);
-    let mut osc_socket = OscSocket::bind(&osc_addr)
+    let osc_socket = OscSocket::bind(&osc_addr)
         .await
         .expect(&format!("Failed to bind osc listener at {}", osc_addr));
 
@@ -96,24 +72,83 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let osc_tx_for_subscribing = osc_tx.clone();
 
     tokio::task::spawn(async move {
-        let mut warned_bundle = false;
-
-        while let Some(osc_packet) = osc_socket.next().await {
-            let (osc_packet, peer_addr) = osc_packet.unwrap();
-            match osc_packet {
-                OscPacket::Message(osc_msg) => {
-                    let json_string =
-                        serde_json::to_string(&types::OscMessageWrapper::new(osc_msg)).unwrap();
-
+        // Received straight off the underlying UDP socket (bypassing `OscSocket`'s decoding
+        // `Stream`) so the raw datagram bytes are still around to forward verbatim in
+        // `OscBinary` mode, instead of re-encoding a decoded `OscMessage`.
+        let mut recv_buf = vec![0u8; 1024 * 64];
+        loop {
+            let (n, peer_addr) = match osc_socket.socket().recv_from(&mut recv_buf).await {
+                Ok(v) => v,
+                Err(e) => {
+                    println!("FATAL: OSC receiver socket error: {}", e);
+                    break;
+                }
+            };
+            let raw = recv_buf[..n].to_vec();
+            let osc_packet = match rosc::decoder::decode(&raw) {
+                Ok(p) => p,
+                Err(e) => {
                     if configs.debug {
-                        println!("Received OSC message from {}: {}", peer_addr, json_string);
+                        println!("Dropping malformed inbound OSC packet from {}: {:?}", peer_addr, e);
                     }
-                    osc_tx.send(json_string).unwrap();
+                    continue;
                 }
-                OscPacket::Bundle(_) => {
-                    if !warned_bundle {
-                        println!("Warning: Received an OSC Bundle from {}, but it is not currently supported and will be ignored.", peer_addr);
-                        warned_bundle = true;
+            };
+            match osc_packet {
+                OscPacket::Message(osc_msg) => {
+                    let address = osc_msg.addr.clone();
+                    let payload = match configs.message_format {
+                        config::MessageFormat::Json => {
+                            let wrapper = types::OscMessageWrapper::new(osc_msg);
+                            let json_string = serde_json::to_string(&wrapper).unwrap();
+                            if configs.debug {
+                                println!("Received OSC message from {}: {}", peer_addr, json_string);
+                            }
+                            types::OscPayload::Text(json_string)
+                        }
+                        config::MessageFormat::OscBinary => {
+                            if configs.debug {
+                                println!("Received OSC message from {} for {} (binary passthrough)", peer_addr, address);
+                            }
+                            types::OscPayload::Binary(raw)
+                        }
+                    };
+                    osc_tx
+                        .send(types::OscBroadcastMessage { address, payload, bypass_filter: false })
+                        .unwrap();
+                }
+                OscPacket::Bundle(bundle) => {
+                    match configs.message_format {
+                        config::MessageFormat::OscBinary => {
+                            // Forwarded as a single raw datagram, byte-for-byte: honoring the
+                            // bundle's time tag (chunk0-2) or per-client address filtering
+                            // (chunk0-3) would require decoding it into individual messages,
+                            // which is exactly the re-encoding this mode exists to avoid. So
+                            // binary-mode bundles deliver immediately to every connected
+                            // client, regardless of subscription filters or the time tag.
+                            if configs.debug {
+                                println!("Received OSC bundle from {} ({} bytes, binary passthrough)", peer_addr, raw.len());
+                            }
+                            let _ = osc_tx.send(types::OscBroadcastMessage {
+                                address: String::new(),
+                                payload: types::OscPayload::Binary(raw),
+                                bypass_filter: true
+                            });
+                        }
+                        config::MessageFormat::Json => {
+                            if configs.debug {
+                                let json_string =
+                                    serde_json::to_string(&types::OscBundleWrapper::new(&bundle)).unwrap();
+                                println!("Received OSC bundle from {}: {}", peer_addr, json_string);
+                            }
+                            tokio::task::spawn(schedule_bundle(
+                                bundle,
+                                osc_tx.clone(),
+                                peer_addr,
+                                configs.debug,
+                                configs.max_bundle_delay_secs,
+                            ));
+                        }
                     }
                 }
             }
@@ -122,6 +157,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("OSC receiver is listening at {}", osc_addr);
 
+    // _____________________________________________________________________________________________________________________
+    //
+    // Setup outbound OSC socket, used to forward WebSocket messages back out as OSC.
+    // _____________________________________________________________________________________________________________________
+
+    let osc_out_socket = OscSocket::bind("0.0.0.0:0")
+        .await
+        .expect("Failed to bind outbound OSC socket");
+    let osc_out_socket = Arc::new(osc_out_socket);
+    let osc_out_targets = configs.osc_out_targets.clone();
+    let debug = configs.debug;
+
     // _____________________________________________________________________________________________________________________
     //
     // Setup websocket handler. WSS path is root: /
@@ -130,38 +177,113 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let websocket_path = warp::path::end() // <- Specifies Root path "https://localhost/"
         .and(warp::ws())
         .and(warp::any().map(move || osc_tx_for_subscribing.clone()))
+        .and(warp::any().map(move || (osc_out_targets.clone(), osc_out_socket.clone(), debug)))
         .and(warp::filters::addr::remote()) // to get client ip addr as param
         .map(
             |ws: warp::ws::Ws,
-             osc_tx: tokio::sync::broadcast::Sender<String>,
+             osc_tx: tokio::sync::broadcast::Sender<types::OscBroadcastMessage>,
+             (osc_out_targets, osc_out_socket, debug): (Vec<String>, Arc<OscSocket>, bool),
              addr: Option<SocketAddr>| {
-                ws.on_upgrade(move |mut websocket| {
+                ws.on_upgrade(move |websocket| {
                     let ip_str = addr.and_then(|x| Some(x.ip().to_string()))
                         .unwrap_or("unknown ip".to_string());
                     println!("Websocket client connected: {}", ip_str);
                     async move {
+                        let (mut ws_tx, mut ws_rx) = websocket.split();
                         let mut osc_rx = osc_tx.subscribe();
-                        loop {
-                            match osc_rx.recv().await {
-                                Ok(msg) => {
-                                    let send_res = websocket
-                                        .send(warp::ws::Message::text(msg))
-                                        .await;
-                                    match send_res {
-                                        Ok(_) => {},
-                                        Err(e) => {
-                                            println!("Closing websocket connection with client {} due to disconnection/error: {}", ip_str, e);
-                                            let _ = websocket.close().await;
-                                            break;
-                                        },
+
+                        // Empty means "no filter set", i.e. receive everything.
+                        let subscriptions: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+
+                        // Forward internal OSC broadcasts out to this client, filtered by
+                        // whatever address patterns it last subscribed to.
+                        let ip_str_for_send = ip_str.clone();
+                        let subscriptions_for_send = subscriptions.clone();
+                        let send_task = tokio::task::spawn(async move {
+                            loop {
+                                match osc_rx.recv().await {
+                                    Ok(broadcast_msg) => {
+                                        let subscribed = broadcast_msg.bypass_filter || {
+                                            let patterns = subscriptions_for_send.lock().await;
+                                            patterns.is_empty()
+                                                || patterns.iter().any(|p| matcher::matches(&broadcast_msg.address, p))
+                                        };
+                                        if !subscribed {
+                                            continue;
+                                        }
+
+                                        let ws_message = match broadcast_msg.payload {
+                                            types::OscPayload::Text(s) => warp::ws::Message::text(s),
+                                            types::OscPayload::Binary(b) => warp::ws::Message::binary(b)
+                                        };
+                                        let send_res = ws_tx.send(ws_message).await;
+                                        match send_res {
+                                            Ok(_) => {},
+                                            Err(e) => {
+                                                println!("Closing websocket connection with client {} due to disconnection/error: {}", ip_str_for_send, e);
+                                                let _ = ws_tx.close().await;
+                                                break;
+                                            },
+                                        }
+                                    },
+                                    Err(e) => {
+                                        println!("FATAL: couldn't recv from internal broadcast channel osc_rx: {}", e);
+                                        break;
                                     }
-                                },
+                                }
+                            }
+                        });
+
+                        // Forward messages sent by this client back out as OSC, or update its
+                        // address-pattern subscription if it sent a control frame instead.
+                        while let Some(ws_msg) = ws_rx.next().await {
+                            let ws_msg = match ws_msg {
+                                Ok(m) => m,
                                 Err(e) => {
-                                    println!("FATAL: couldn't recv from internal broadcast channel osc_rx: {}", e);
+                                    println!("Websocket client {} disconnected: {}", ip_str, e);
                                     break;
                                 }
+                            };
+
+                            if ws_msg.is_close() {
+                                break;
+                            }
+
+                            if !ws_msg.is_text() && !ws_msg.is_binary() {
+                                continue;
+                            }
+
+                            // Both frame kinds carry JSON: binary is accepted alongside text
+                            // for clients that prefer sending JSON as a binary frame.
+                            let json_bytes = ws_msg.as_bytes();
+
+                            if let Ok(subscribe_frame) = serde_json::from_slice::<SubscribeFrame>(json_bytes) {
+                                let mut patterns = subscriptions.lock().await;
+                                *patterns = subscribe_frame.subscribe;
+                                continue;
+                            }
+
+                            let wrapper: types::OscMessageWrapper = match serde_json::from_slice(json_bytes) {
+                                Ok(w) => w,
+                                Err(e) => {
+                                    if debug {
+                                        println!("Dropping malformed inbound OSC JSON from {}: {}", ip_str, e);
+                                    }
+                                    continue;
+                                }
+                            };
+
+                            let osc_packet = OscPacket::Message(wrapper.into_osc());
+                            for target in &osc_out_targets {
+                                if let Err(e) = osc_out_socket.send_to(osc_packet.clone(), target.as_str()).await {
+                                    if debug {
+                                        println!("Failed to forward OSC message to {}: {}", target, e);
+                                    }
+                                }
                             }
                         }
+
+                        send_task.abort();
                     }
                 })
             },
@@ -182,10 +304,154 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     warp::serve(routes)
         .tls()
-        .cert_path("./certs/cert.pem")
-        .key_path("./certs/key.pem")
+        .cert_path(tls_cert_path)
+        .key_path(tls_key_path)
         .run(([0, 0, 0, 0], configs.wss_port))
         .await;
 
     Ok(())
 }
+
+/// Generates a self-signed X.509 cert every time the server starts without a user-supplied
+/// cert/key pair (`cert_path`/`key_path` both set and present on disk), since the machine may
+/// be reachable under a different set of addresses on each run. Every local network
+/// interface (VPN, second NIC, Wi-Fi vs Ethernet, ...) is added as a `subject_alt_name`
+/// alongside `localhost`, so clients reaching the box via any of them don't hit a TLS
+/// hostname mismatch.
+fn generate_self_signed_cert() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cert_domain_names = vec!["localhost".to_string()];
+    for (_, ip) in list_afinet_netifas().expect("failed to enumerate local network interfaces") {
+        let ip_str = ip.to_string();
+        if !cert_domain_names.contains(&ip_str) {
+            cert_domain_names.push(ip_str);
+        }
+    }
+
+    let subject_alt_names: Vec<SanType> = cert_domain_names
+        .iter()
+        .map(|name| match name.parse::<IpAddr>() {
+            Ok(ip) => SanType::IpAddress(ip),
+            Err(_) => SanType::DnsName(name.clone())
+        })
+        .collect();
+
+    let mut cert_params: CertificateParams = Default::default();
+    cert_params.not_before = date_time_ymd(2023, 07, 01);
+    cert_params.not_after = date_time_ymd(4096, 01, 01);
+    cert_params.distinguished_name = DistinguishedName::new();
+    cert_params.alg = &rcgen::PKCS_RSA_SHA256;
+    cert_params.subject_alt_names = subject_alt_names;
+
+    fs::create_dir_all("certs")?; // make certs dir if it doesn't exist
+
+    let priv_key_pem = {
+        // If local priv key not already created, make one.
+        if let Ok(pk) = fs::read("./certs/priv_key_rsa.pem") {
+            pk
+        } else {
+            let rsa = Rsa::generate(2048)?;
+            let rsa_pem_str = rsa.private_key_to_pem()?;
+            fs::write("./certs/priv_key_rsa.pem", &rsa_pem_str)?;
+            rsa_pem_str
+        }
+    };
+    let priv_key =
+        Rsa::private_key_from_pem(&priv_key_pem).expect("couldn't parse priv_key_rsa.pem");
+    let pkey =
+        openssl::pkey::PKey::from_rsa(priv_key).expect("couldn't convert priv_key_rsa.pem to pkey");
+    let key_pair_pem = String::from_utf8(
+        pkey.private_key_to_pem_pkcs8()
+            .expect("fail convert to pem pkcs8"),
+    )
+    .unwrap();
+
+    let key_pair = rcgen::KeyPair::from_pem(&key_pair_pem).expect("failed to make KeyPair");
+
+    cert_params.key_pair = Some(key_pair);
+
+    let cert = rcgen::Certificate::from_params(cert_params).expect("failed to make Certificate");
+    let cert_pem_serialized = cert
+        .serialize_pem()
+        .expect("failed to serialize cert to pem");
+    fs::write("./certs/cert.pem", &cert_pem_serialized.as_bytes())
+        .expect("failed to write to file certs/cert.pem");
+    fs::write(
+        "./certs/key.pem",
+        &cert.serialize_private_key_pem().as_bytes(),
+    )
+    .expect("failed to write to file certs/key.pem");
+
+    println!(
+        "Created TLS cert for domains: {}",
+        cert_domain_names.join(", ")
+    );
+
+    Ok(())
+}
+
+/// Waits until `bundle`'s time tag is due (if it isn't already) and then broadcasts each
+/// contained OSC message, recursing into nested bundles. Immediate-dispatch (`(0, 1)`) and
+/// past time tags are delivered right away; time tags further out than
+/// `max_bundle_delay_secs` are dropped so a bogus far-future schedule can't leak a sleeping
+/// task forever.
+///
+/// Only used for `MessageFormat::Json` delivery: `MessageFormat::OscBinary` forwards bundles
+/// as a single raw datagram instead (see the `OscPacket::Bundle` arm in `main`), so per-message
+/// scheduling and address filtering don't apply to them.
+fn schedule_bundle(
+    bundle: OscBundle,
+    osc_tx: broadcast::Sender<types::OscBroadcastMessage>,
+    peer_addr: SocketAddr,
+    debug: bool,
+    max_bundle_delay_secs: u64
+) -> Pin<Box<dyn std::future::Future<Output = ()> + Send>> {
+    Box::pin(async move {
+        const IMMEDIATELY: (u32, u32) = (0, 1);
+
+        if bundle.timetag != IMMEDIATELY {
+            let target_epoch_ms = types::ntp_to_epoch_ms(bundle.timetag.0, bundle.timetag.1);
+            let now_epoch_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0);
+
+            if target_epoch_ms > now_epoch_ms {
+                let delay_ms = target_epoch_ms - now_epoch_ms;
+
+                if delay_ms > max_bundle_delay_secs * 1000 {
+                    println!(
+                        "Warning: Dropping OSC bundle from {} scheduled {} ms in the future, exceeds max_bundle_delay_secs={}",
+                        peer_addr, delay_ms, max_bundle_delay_secs
+                    );
+                    return;
+                }
+
+                tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            }
+        }
+
+        for packet in bundle.content {
+            match packet {
+                OscPacket::Message(osc_msg) => {
+                    let address = osc_msg.addr.clone();
+                    let wrapper = types::OscMessageWrapper::new(osc_msg);
+                    let json_string = serde_json::to_string(&wrapper).unwrap();
+                    if debug {
+                        println!("Delivering scheduled OSC message from {}: {}", peer_addr, json_string);
+                    }
+                    let _ = osc_tx.send(types::OscBroadcastMessage {
+                        address,
+                        payload: types::OscPayload::Text(json_string),
+                        bypass_filter: false
+                    });
+                }
+                OscPacket::Bundle(nested) => {
+                    // Spawned rather than awaited inline: a nested bundle's own (possibly
+                    // future) timetag must not delay sibling packets that follow it in
+                    // `bundle.content`.
+                    tokio::task::spawn(schedule_bundle(nested, osc_tx.clone(), peer_addr, debug, max_bundle_delay_secs));
+                }
+            }
+        }
+    })
+}
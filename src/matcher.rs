@@ -0,0 +1,177 @@
+//! OSC 1.0 address-pattern matching, used to test whether a WebSocket client's
+//! subscribed patterns (e.g. `/synth/*`, `/mixer/[0-9]/gain`) match an incoming
+//! OSC message's address before it is sent to that client.
+
+/// Tests `address` (e.g. `/synth/foo`) against `pattern` (e.g. `/synth/*`), per the OSC 1.0
+/// address-pattern syntax: `?` (any single char), `*` (any run of chars), `[a-z]`/`[!a-z]`
+/// (character classes, optionally negated), and `{foo,bar}` (alternation). Matching is done
+/// slash-delimited part by part: `address` and `pattern` must have the same number of `/`
+/// separated parts, and each part is matched independently.
+pub fn matches(address: &str, pattern: &str) -> bool {
+    let addr_parts: Vec<&str> = address.split('/').collect();
+    let pattern_parts: Vec<&str> = pattern.split('/').collect();
+
+    if addr_parts.len() != pattern_parts.len() {
+        return false;
+    }
+
+    addr_parts
+        .iter()
+        .zip(pattern_parts.iter())
+        .all(|(a, p)| match_part(&a.chars().collect::<Vec<_>>(), &p.chars().collect::<Vec<_>>()))
+}
+
+fn match_part(input: &[char], pattern: &[char]) -> bool {
+    match pattern.first() {
+        None => input.is_empty(),
+        Some('*') => {
+            match_part(input, &pattern[1..]) || (!input.is_empty() && match_part(&input[1..], pattern))
+        }
+        Some('?') => !input.is_empty() && match_part(&input[1..], &pattern[1..]),
+        Some('[') => match parse_char_class(pattern) {
+            Some((class, rest)) => !input.is_empty() && class.contains(input[0]) && match_part(&input[1..], rest),
+            None => false
+        },
+        Some('{') => match parse_alternation(pattern) {
+            Some((alts, rest)) => alts.into_iter().any(|alt| {
+                let combined: Vec<char> = alt.into_iter().chain(rest.iter().copied()).collect();
+                match_part(input, &combined)
+            }),
+            None => false
+        },
+        Some(&c) => !input.is_empty() && input[0] == c && match_part(&input[1..], &pattern[1..])
+    }
+}
+
+struct CharClass {
+    chars: Vec<char>,
+    negate: bool
+}
+
+impl CharClass {
+    fn contains(&self, c: char) -> bool {
+        let mut i = 0;
+        let mut found = false;
+
+        while i < self.chars.len() {
+            if i + 2 < self.chars.len() && self.chars[i + 1] == '-' {
+                if c >= self.chars[i] && c <= self.chars[i + 2] {
+                    found = true;
+                }
+                i += 3;
+            } else {
+                if self.chars[i] == c {
+                    found = true;
+                }
+                i += 1;
+            }
+        }
+
+        found != self.negate
+    }
+}
+
+/// `pattern[0]` must be `'['`. Returns the parsed class and the remaining pattern after `']'`.
+fn parse_char_class(pattern: &[char]) -> Option<(CharClass, &[char])> {
+    let mut i = 1;
+    let negate = pattern.get(i) == Some(&'!');
+    if negate {
+        i += 1;
+    }
+    let start = i;
+
+    while i < pattern.len() && pattern[i] != ']' {
+        i += 1;
+    }
+    if i >= pattern.len() {
+        return None;
+    }
+
+    Some((
+        CharClass { chars: pattern[start..i].to_vec(), negate },
+        &pattern[i + 1..]
+    ))
+}
+
+/// `pattern[0]` must be `'{'`. Returns the comma-separated alternatives and the remaining
+/// pattern after the matching `'}'`.
+fn parse_alternation(pattern: &[char]) -> Option<(Vec<Vec<char>>, &[char])> {
+    let mut depth = 0;
+    let mut alt_start = 1;
+    let mut alts = Vec::new();
+
+    for i in 0..pattern.len() {
+        match pattern[i] {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    alts.push(pattern[alt_start..i].to_vec());
+                    return Some((alts, &pattern[i + 1..]));
+                }
+            }
+            ',' if depth == 1 => {
+                alts.push(pattern[alt_start..i].to_vec());
+                alt_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn exact_match() {
+        assert!(matches("/synth/foo", "/synth/foo"));
+        assert!(!matches("/synth/foo", "/synth/bar"));
+    }
+
+    #[test]
+    fn star_matches_any_run_within_a_part() {
+        assert!(matches("/synth/foo", "/synth/*"));
+        assert!(matches("/synth/", "/synth/*"));
+        assert!(matches("/synth/foobar", "/synth/foo*"));
+        assert!(!matches("/synth/foo/bar", "/synth/*"));
+    }
+
+    #[test]
+    fn question_mark_matches_exactly_one_char() {
+        assert!(matches("/synth/fog", "/synth/fo?"));
+        assert!(!matches("/synth/fo", "/synth/fo?"));
+        assert!(!matches("/synth/foog", "/synth/fo?"));
+    }
+
+    #[test]
+    fn char_class_matches_any_char_in_range_or_set() {
+        assert!(matches("/mixer/5/gain", "/mixer/[0-9]/gain"));
+        assert!(!matches("/mixer/x/gain", "/mixer/[0-9]/gain"));
+        assert!(matches("/track/a", "/track/[abc]"));
+        assert!(!matches("/track/d", "/track/[abc]"));
+    }
+
+    #[test]
+    fn negated_char_class_matches_any_char_not_in_set() {
+        assert!(matches("/track/d", "/track/[!abc]"));
+        assert!(!matches("/track/a", "/track/[!abc]"));
+        assert!(matches("/mixer/x/gain", "/mixer/[!0-9]/gain"));
+        assert!(!matches("/mixer/5/gain", "/mixer/[!0-9]/gain"));
+    }
+
+    #[test]
+    fn alternation_matches_any_listed_option() {
+        assert!(matches("/synth/foo", "/synth/{foo,bar}"));
+        assert!(matches("/synth/bar", "/synth/{foo,bar}"));
+        assert!(!matches("/synth/baz", "/synth/{foo,bar}"));
+    }
+
+    #[test]
+    fn unequal_part_counts_never_match() {
+        assert!(!matches("/synth/foo/bar", "/synth/foo"));
+        assert!(!matches("/synth", "/synth/*"));
+    }
+}
@@ -1,5 +1,38 @@
-use async_osc::{OscMessage, OscType};
+use async_osc::{OscBundle, OscMessage, OscPacket, OscType};
+use rosc::{OscArray, OscColor, OscMidiMessage};
+use serde::de::{self, Deserialize, Deserializer};
 use serde::ser::{Serialize, Serializer, SerializeStruct};
+use serde_json::Value;
+
+/// An OSC message in transit on the internal broadcast channel: the address is kept
+/// alongside the already-encoded payload so per-client subscription filters (see the
+/// `matcher` module) can test it without decoding `payload`.
+#[derive(Clone, Debug)]
+pub struct OscBroadcastMessage {
+    pub address: String,
+    pub payload: OscPayload,
+    /// Set for raw `OscBinary`-mode bundle passthrough (see the `OscPacket::Bundle` arm in
+    /// `main`), where there's no single per-message address to filter on. `true` here skips
+    /// every client's subscription check and delivers unconditionally.
+    pub bypass_filter: bool
+}
+
+/// The two wire formats a broadcast payload can be delivered in, matching
+/// `ConfigToml::message_format`.
+#[derive(Clone, Debug)]
+pub enum OscPayload {
+    Text(String),
+    Binary(Vec<u8>)
+}
+
+/// Converts an NTP `(seconds since 1900, fractional seconds)` pair into milliseconds since
+/// the Unix epoch, per the OSC time-tag format. Shared by the `Time` arg serializer and
+/// `OscBundleWrapper`'s `timeTag`.
+pub fn ntp_to_epoch_ms(secs_since_1900: u32, frac_secs: u32) -> u64 {
+    let secs_since_1970 = secs_since_1900 as f64 - 2_208_988_800_f64;
+    let decimals = (frac_secs as f64) / 4_294_967_296_f64;
+    ((secs_since_1970 + decimals) * 1000.0) as u64
+}
 
 pub enum OscTypeWrapper {
     Int(i32),
@@ -44,7 +77,7 @@ impl OscTypeWrapper {
     }
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct OscColorWrapper {
     pub r: u8,
     pub g: u8,
@@ -52,7 +85,7 @@ pub struct OscColorWrapper {
     pub a: u8
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct OscMessageWrapper {
     pub address: String,
     pub args: Vec<OscTypeWrapper>
@@ -68,6 +101,15 @@ impl OscMessageWrapper {
             args
         }
     }
+
+    /// Reverse of `new`: turns a wrapper parsed from an inbound WebSocket message
+    /// back into an `async_osc::OscMessage` ready to be sent out over OSC.
+    pub fn into_osc(self) -> OscMessage {
+        OscMessage {
+            addr: self.address,
+            args: self.args.into_iter().map(|arg| arg.into_osc()).collect()
+        }
+    }
 }
 
 impl Serialize for OscTypeWrapper {
@@ -103,11 +145,8 @@ impl Serialize for OscTypeWrapper {
                 let mut state = serializer.serialize_struct("OscTypeWrapper", 2)?;
                 // NOTE: 'rawNTP' is used instead of 'raw' in OSC.js.
                 state.serialize_field("rawNTP", &[secs_since_1900, frac_secs])?;
-
-                let secs_since_1970 = (secs_since_1900 - 2_208_988_800) as f64;
-                let decimals = (*frac_secs as f64) / 4_294_967_296_f64;
                 // NOTE: 'epochTimeMs' used instead of 'native' in OSC.js.
-                state.serialize_field("epochTimeMs", &(((secs_since_1970 + decimals) * 1000.0) as u64))?;
+                state.serialize_field("epochTimeMs", &ntp_to_epoch_ms(*secs_since_1900, *frac_secs))?;
                 state.end()
             },
             OscTypeWrapper::Long(h) => {
@@ -151,4 +190,160 @@ impl Serialize for OscTypeWrapper {
             OscTypeWrapper::Inf => serializer.serialize_f32(1.0),
         }
     }
+}
+
+impl OscTypeWrapper {
+    /// Reverse of `new`: maps a wrapper parsed from inbound JSON back to the
+    /// matching `async_osc::OscType` variant.
+    pub fn into_osc(self) -> OscType {
+        match self {
+            OscTypeWrapper::Int(i) => OscType::Int(i),
+            OscTypeWrapper::Float(f) => OscType::Float(f),
+            OscTypeWrapper::String(s) => OscType::String(s),
+            OscTypeWrapper::Blob(b) => OscType::Blob(b),
+            OscTypeWrapper::Time(t) => OscType::Time(t),
+            OscTypeWrapper::Long(l) => OscType::Long(l),
+            OscTypeWrapper::Double(d) => OscType::Double(d),
+            OscTypeWrapper::Char(c) => OscType::Char(c),
+            OscTypeWrapper::Color(c) => OscType::Color(OscColor {
+                red: c.r,
+                green: c.g,
+                blue: c.b,
+                alpha: c.a
+            }),
+            OscTypeWrapper::Midi(m) => OscType::Midi(OscMidiMessage {
+                port: m[0],
+                status: m[1],
+                data1: m[2],
+                data2: m[3]
+            }),
+            OscTypeWrapper::Bool(b) => OscType::Bool(b),
+            OscTypeWrapper::Array(a) => OscType::Array(OscArray {
+                content: a.into_iter().map(|t| t.into_osc()).collect()
+            }),
+            OscTypeWrapper::Nil => OscType::Nil,
+            OscTypeWrapper::Inf => OscType::Inf
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for OscTypeWrapper {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de> {
+        let value = Value::deserialize(deserializer)?;
+        Self::from_json_value(&value).map_err(de::Error::custom)
+    }
+}
+
+impl OscTypeWrapper {
+    // Mirrors the schema produced by `Serialize` above: `{ "type": "i"/"f"/"s"/"b"/"h"/"d"/"c"/"r"/"m", "value": ... }`,
+    // plus the bare JSON bool/array/null forms, the `rawNTP` time-tag form, and the bare
+    // JSON number form `Inf` serializes as (every other numeric arg type is tagged, so an
+    // untagged number is unambiguously `Inf`).
+    fn from_json_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Bool(b) => Ok(Self::Bool(*b)),
+            Value::Null => Ok(Self::Nil),
+            Value::Number(_) => Ok(Self::Inf),
+            Value::Array(arr) => Ok(Self::Array(
+                arr.iter()
+                    .map(Self::from_json_value)
+                    .collect::<Result<Vec<_>, _>>()?
+            )),
+            Value::Object(map) => {
+                if let Some(raw) = map.get("rawNTP") {
+                    let (secs, frac) = serde_json::from_value::<(u32, u32)>(raw.clone())
+                        .map_err(|e| format!("invalid rawNTP pair: {}", e))?;
+                    return Ok(Self::Time((secs, frac)));
+                }
+
+                let type_tag = map
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| "missing \"type\" tag on OSC arg".to_string())?;
+                let val = map
+                    .get("value")
+                    .ok_or_else(|| format!("missing \"value\" for OSC arg of type \"{}\"", type_tag))?;
+
+                match type_tag {
+                    "i" => Ok(Self::Int(Self::from_value(val)?)),
+                    "f" => Ok(Self::Float(Self::from_value(val)?)),
+                    "s" => Ok(Self::String(Self::from_value(val)?)),
+                    "b" => Ok(Self::Blob(Self::from_value(val)?)),
+                    "h" => Ok(Self::Long(Self::from_value(val)?)),
+                    "d" => Ok(Self::Double(Self::from_value(val)?)),
+                    "c" => Ok(Self::Char(Self::from_value(val)?)),
+                    "r" => Ok(Self::Color(Self::from_value(val)?)),
+                    "m" => {
+                        let bytes: Vec<u8> = Self::from_value(val)?;
+                        if bytes.len() != 4 {
+                            return Err(format!(
+                                "\"m\" (midi) value must have exactly 4 bytes, got {}",
+                                bytes.len()
+                            ));
+                        }
+                        Ok(Self::Midi(bytes))
+                    },
+                    other => Err(format!("unknown OSC arg type tag: \"{}\"", other))
+                }
+            }
+            other => Err(format!("unsupported JSON value for OSC arg: {}", other))
+        }
+    }
+
+    fn from_value<T: serde::de::DeserializeOwned>(value: &Value) -> Result<T, String> {
+        serde_json::from_value(value.clone()).map_err(|e| e.to_string())
+    }
+}
+
+/// An OSC bundle's time tag, serialized the same way as `OscTypeWrapper::Time`.
+pub struct OscTimeTagWrapper {
+    pub raw_ntp: (u32, u32)
+}
+
+impl Serialize for OscTimeTagWrapper {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer {
+        let mut state = serializer.serialize_struct("OscTimeTagWrapper", 2)?;
+        state.serialize_field("rawNTP", &[self.raw_ntp.0, self.raw_ntp.1])?;
+        state.serialize_field("epochTimeMs", &ntp_to_epoch_ms(self.raw_ntp.0, self.raw_ntp.1))?;
+        state.end()
+    }
+}
+
+/// Either of the two things an OSC bundle can contain, serialized untagged so bundle
+/// elements come out as plain messages or plain nested bundles, matching what the OSC
+/// spec itself allows.
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+pub enum OscPacketWrapper {
+    Bundle(OscBundleWrapper),
+    Message(OscMessageWrapper)
+}
+
+impl OscPacketWrapper {
+    pub fn new(packet: &OscPacket) -> Self {
+        match packet {
+            OscPacket::Message(msg) => Self::Message(OscMessageWrapper::new(msg.clone())),
+            OscPacket::Bundle(bundle) => Self::Bundle(OscBundleWrapper::new(bundle))
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct OscBundleWrapper {
+    #[serde(rename = "timeTag")]
+    pub time_tag: OscTimeTagWrapper,
+    pub packets: Vec<OscPacketWrapper>
+}
+
+impl OscBundleWrapper {
+    pub fn new(bundle: &OscBundle) -> Self {
+        Self {
+            time_tag: OscTimeTagWrapper { raw_ntp: bundle.timetag },
+            packets: bundle.content.iter().map(OscPacketWrapper::new).collect()
+        }
+    }
 }
\ No newline at end of file